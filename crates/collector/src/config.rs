@@ -21,6 +21,8 @@ pub struct Config {
     pub proc_dir: String,
     pub hostname_path: String,
     pub server_port: Port,
+    pub log_globs: Vec<String>,
+    pub log_match_pattern: String,
 }
 
 impl Config {
@@ -35,6 +37,15 @@ impl Config {
                 .unwrap_or("/etc/hostname".to_string())
                 .parse()
                 .expect("the environment variable COLLECTOR_SERVER_PORT should be set as a value between 1024 and 65535"),
+            log_globs: std::env::var("COLLECTOR_LOG_GLOBS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(str::to_string)
+                .collect(),
+            log_match_pattern: std::env::var("COLLECTOR_LOG_MATCH_PATTERN")
+                .unwrap_or_else(|_| r"\s5\d{2}\s".to_string()),
         }
     }
 }
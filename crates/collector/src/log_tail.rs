@@ -0,0 +1,69 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use linemux::MuxedLines;
+use regex::Regex;
+use tracing::{error, info, warn};
+
+use crate::Result;
+
+/// Running counters fed by the background tailer task. Cheap to read from
+/// the metrics-collection path since it's just a couple of atomic loads.
+#[derive(Default)]
+pub struct LogStats {
+    total_lines: AtomicU64,
+    matched_lines: AtomicU64,
+}
+
+impl LogStats {
+    pub fn total_lines(&self) -> u64 {
+        self.total_lines.load(Ordering::Relaxed)
+    }
+
+    pub fn matched_lines(&self) -> u64 {
+        self.matched_lines.load(Ordering::Relaxed)
+    }
+}
+
+/// Tails every file matching `globs` from its current end, handling
+/// rotation/truncation via `linemux`, and keeps running counters of total
+/// lines seen and lines matching `match_pattern` (e.g. HTTP 5xx statuses).
+pub async fn spawn(globs: &[String], match_pattern: &str) -> Result<Arc<LogStats>> {
+    let matcher = Regex::new(match_pattern)?;
+    let stats = Arc::new(LogStats::default());
+
+    let mut lines = MuxedLines::new()?;
+    for pattern in globs {
+        for entry in glob::glob(pattern)? {
+            match entry {
+                Ok(path) => {
+                    info!("Tailing {}", path.display());
+                    if let Err(e) = lines.add_file(&path).await {
+                        warn!("Could not tail {}: {e}", path.display());
+                    }
+                }
+                Err(e) => warn!("Error expanding log glob {pattern}: {e}"),
+            }
+        }
+    }
+
+    let task_stats = stats.clone();
+    tokio::spawn(async move {
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    task_stats.total_lines.fetch_add(1, Ordering::Relaxed);
+                    if matcher.is_match(line.line()) {
+                        task_stats.matched_lines.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => error!("Error tailing logs: {e}"),
+            }
+        }
+    });
+
+    Ok(stats)
+}
@@ -1,18 +1,21 @@
 use std::{
     path::{Path, PathBuf},
+    sync::Arc,
     time::Duration,
 };
 
 use metrics_core::{
-    metrics_service_server::{MetricsService, MetricsServiceServer},
-    registration_service_client::RegistrationServiceClient,
-    Memory, MetricsRequest, MetricsResponse, RegistrationRequest,
+    metrics_service_client::MetricsServiceClient,
+    registration_service_client::RegistrationServiceClient, LogMetrics, Memory, MetricsResponse,
+    RegistrationRequest,
 };
-use tonic::{transport::Server, Request, Response, Status};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::{error, info, level_filters::LevelFilter, warn};
 use tracing_subscriber::EnvFilter;
 
 mod config;
+mod log_tail;
 
 pub type Result<T> = core::result::Result<T, Box<dyn std::error::Error + 'static>>;
 
@@ -29,20 +32,6 @@ async fn main() -> Result<()> {
         .init();
 
     println!("Collector started");
-    let grpc_addr = format!("127.0.0.1:{}", config.server_port).parse().unwrap();
-
-    let handle = tokio::spawn(async move {
-        info!("Starting server at {grpc_addr}");
-        Server::builder()
-            .add_service(MetricsServiceServer::new(CollectorMetricService::new(
-                &config.hostname_path,
-                &config.proc_dir,
-            )))
-            .serve(grpc_addr)
-            .await
-    });
-
-    tokio::time::sleep(Duration::from_secs(1)).await;
 
     let mut connection_attempts = 0;
     let mut registration_client = loop {
@@ -80,47 +69,73 @@ async fn main() -> Result<()> {
         }
     }
 
-    handle.await??;
+    let log_stats = log_tail::spawn(&config.log_globs, &config.log_match_pattern)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Log tailing disabled: {e}");
+            Arc::new(log_tail::LogStats::default())
+        });
+
+    run_metrics_stream(&config, &log_stats).await?;
 
     Ok(())
 }
 
-struct CollectorMetricService {
-    hostname_file: String,
-    proc_dir: String,
-}
+/// Opens the long-lived, collector-initiated stream to the web server and
+/// services `MetricsRequest`s off it for as long as the connection lasts.
+/// The collector never accepts inbound connections, so it works fine behind
+/// NAT or an outbound-only firewall.
+async fn run_metrics_stream(config: &config::Config, log_stats: &log_tail::LogStats) -> Result<()> {
+    let mut metrics_client = MetricsServiceClient::connect(config.web_url.clone()).await?;
+
+    let (tx, rx) = mpsc::channel(8);
+    let mut inbound = metrics_client
+        .stream(ReceiverStream::new(rx))
+        .await?
+        .into_inner();
+
+    // The first message doubles as this collector's introduction: it lets
+    // the web server learn our host before it has anything to ask us for.
+    tx.send(collect_metrics(&config.hostname_path, &config.proc_dir, log_stats).await?)
+        .await?;
 
-impl CollectorMetricService {
-    pub fn new(host: impl Into<String>, proc_dir: impl Into<String>) -> Self {
-        Self {
-            hostname_file: host.into(),
-            proc_dir: proc_dir.into(),
+    while let Some(_request) = inbound.message().await? {
+        let response = collect_metrics(&config.hostname_path, &config.proc_dir, log_stats).await?;
+        if tx.send(response).await.is_err() {
+            warn!("Web server dropped the metrics stream");
+            break;
         }
     }
+
+    Ok(())
 }
 
-#[tonic::async_trait]
-impl MetricsService for CollectorMetricService {
-    async fn request_metrics(
-        &self,
-        _request: Request<MetricsRequest>,
-    ) -> core::result::Result<Response<MetricsResponse>, Status> {
-        Ok(Response::new(MetricsResponse {
-            host: hostname(&self.hostname_file).await.map_err(|e| {
-                warn!("Error: {e}");
-                Status::internal("Could not get hostname")
-            })?,
-            cpu_usage: 0.0,
-            memory: memory_usage(&self.proc_dir)
-                .await
-                .map_err(|e| {
-                    warn!("Error: {e}");
-                    Status::internal("Could not get memory")
-                })
-                .ok(),
-            net_usage: 0,
-        }))
-    }
+async fn collect_metrics(
+    hostname_file: impl AsRef<Path>,
+    proc_dir: impl AsRef<Path>,
+    log_stats: &log_tail::LogStats,
+) -> Result<MetricsResponse> {
+    let proc_dir = proc_dir.as_ref();
+
+    Ok(MetricsResponse {
+        host: hostname(hostname_file).await?,
+        cpu_usage: cpu_usage(proc_dir)
+            .await
+            .inspect_err(|e| warn!("Error: {e}"))
+            .unwrap_or_default(),
+        memory: memory_usage(proc_dir)
+            .await
+            .inspect_err(|e| warn!("Error: {e}"))
+            .ok(),
+        log: Some(LogMetrics {
+            total_lines: log_stats.total_lines(),
+            matched_lines: log_stats.matched_lines(),
+        }),
+        net_usage: net_usage(proc_dir)
+            .await
+            .inspect_err(|e| warn!("Error: {e}"))
+            .unwrap_or_default(),
+    })
 }
 
 async fn hostname(etc_hostname_path: impl AsRef<Path>) -> Result<String> {
@@ -141,24 +156,19 @@ async fn memory_usage(proc_dir: impl Into<PathBuf>) -> Result<Memory> {
 
     for line in file.lines() {
         if line.starts_with("MemTotal") {
-            let mut parts = line.split_whitespace();
-            mem_total = parts.nth(1).unwrap().parse::<u64>().unwrap() * 1000;
+            mem_total = parse_meminfo_kb(line)?;
         }
         if line.starts_with("MemAvailable") {
-            let mut parts = line.split_whitespace();
-            mem_available = parts.nth(1).unwrap().parse::<u64>().unwrap() * 1000;
+            mem_available = parse_meminfo_kb(line)?;
         }
         if line.starts_with("MemFree") {
-            let mut parts = line.split_whitespace();
-            mem_free = parts.nth(1).unwrap().parse::<u64>().unwrap() * 1000;
+            mem_free = parse_meminfo_kb(line)?;
         }
         if line.starts_with("Buffers") {
-            let mut parts = line.split_whitespace();
-            buffers = parts.nth(1).unwrap().parse::<u64>().unwrap() * 1000;
+            buffers = parse_meminfo_kb(line)?;
         }
         if line.starts_with("Cached") {
-            let mut parts = line.split_whitespace();
-            cached = parts.nth(1).unwrap().parse::<u64>().unwrap() * 1000;
+            cached = parse_meminfo_kb(line)?;
         }
     }
 
@@ -170,3 +180,132 @@ async fn memory_usage(proc_dir: impl Into<PathBuf>) -> Result<Memory> {
         cached,
     })
 }
+
+fn parse_meminfo_kb(line: &str) -> Result<u64> {
+    let value = line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| format!("malformed /proc/meminfo line: {line}"))?
+        .parse::<u64>()?;
+    Ok(value * 1000)
+}
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+async fn cpu_usage(proc_dir: impl Into<PathBuf>) -> Result<f64> {
+    let proc_dir: PathBuf = proc_dir.into();
+
+    let first = read_cpu_stat(&proc_dir).await?;
+    tokio::time::sleep(SAMPLE_INTERVAL).await;
+    let second = read_cpu_stat(&proc_dir).await?;
+
+    let total_delta = second.total().saturating_sub(first.total());
+    if total_delta == 0 {
+        return Ok(0.0);
+    }
+
+    let idle_delta = second.idle_all().saturating_sub(first.idle_all());
+
+    Ok(1.0 - (idle_delta as f64 / total_delta as f64))
+}
+
+struct CpuStat {
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+}
+
+impl CpuStat {
+    fn idle_all(&self) -> u64 {
+        self.idle + self.iowait
+    }
+
+    fn total(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+}
+
+async fn read_cpu_stat(proc_dir: impl AsRef<Path>) -> Result<CpuStat> {
+    let mut stat_path = proc_dir.as_ref().to_path_buf();
+    stat_path.push("stat");
+    let file = tokio::fs::read_to_string(stat_path).await?;
+
+    let line = file
+        .lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or("missing aggregate cpu line in /proc/stat")?;
+
+    let mut fields = line.split_whitespace().skip(1);
+    let mut next_field = || -> Result<u64> {
+        Ok(fields
+            .next()
+            .ok_or("malformed /proc/stat cpu line")?
+            .parse::<u64>()?)
+    };
+
+    Ok(CpuStat {
+        user: next_field()?,
+        nice: next_field()?,
+        system: next_field()?,
+        idle: next_field()?,
+        iowait: next_field()?,
+        irq: next_field()?,
+        softirq: next_field()?,
+        steal: next_field()?,
+    })
+}
+
+async fn net_usage(proc_dir: impl Into<PathBuf>) -> Result<u64> {
+    let proc_dir: PathBuf = proc_dir.into();
+
+    let first = read_net_bytes(&proc_dir).await?;
+    tokio::time::sleep(SAMPLE_INTERVAL).await;
+    let second = read_net_bytes(&proc_dir).await?;
+
+    let delta = second.saturating_sub(first);
+    let rate = delta as f64 / SAMPLE_INTERVAL.as_secs_f64();
+
+    Ok(rate as u64)
+}
+
+async fn read_net_bytes(proc_dir: impl AsRef<Path>) -> Result<u64> {
+    let mut net_dev_path = proc_dir.as_ref().to_path_buf();
+    net_dev_path.push("net/dev");
+    let file = tokio::fs::read_to_string(net_dev_path).await?;
+
+    let mut total = 0u64;
+    for line in file.lines().skip(2) {
+        let Some((iface, fields)) = line.split_once(':') else {
+            continue;
+        };
+        if iface.trim() == "lo" {
+            continue;
+        }
+
+        let mut fields = fields.split_whitespace();
+        let received = fields
+            .next()
+            .ok_or("malformed /proc/net/dev line")?
+            .parse::<u64>()?;
+        let transmitted = fields
+            .nth(7)
+            .ok_or("malformed /proc/net/dev line")?
+            .parse::<u64>()?;
+
+        total += received + transmitted;
+    }
+
+    Ok(total)
+}
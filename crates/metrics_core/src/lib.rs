@@ -0,0 +1 @@
+tonic::include_proto!("metrics_core");
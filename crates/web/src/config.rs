@@ -7,6 +7,8 @@ pub async fn config() -> &'static Config {
 pub struct Config {
     pub grpc: GRPCConfig,
     pub web: WebConfig,
+    pub stale_after_secs: u64,
+    pub history_capacity: usize,
 }
 
 impl Config {
@@ -20,6 +22,12 @@ impl Config {
                 host: env_or_default("WEB_WEB_HOST", "127.0.0.1"),
                 port: env_or_default("WEB_WEB_PORT", "3000"),
             },
+            stale_after_secs: env_or_default("WEB_STALE_AFTER_SECS", "60")
+                .parse()
+                .expect("the environment variable WEB_STALE_AFTER_SECS should be a number of seconds"),
+            history_capacity: env_or_default("WEB_HISTORY_CAPACITY", "120")
+                .parse()
+                .expect("the environment variable WEB_HISTORY_CAPACITY should be a number of samples"),
         }
     }
 }
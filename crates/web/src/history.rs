@@ -0,0 +1,49 @@
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use metrics_core::MetricsResponse;
+
+/// One retained sample: a collector's response and when it was captured.
+pub struct Sample {
+    pub timestamp: u64,
+    pub response: MetricsResponse,
+}
+
+/// A bounded per-host ring buffer of recent samples; the oldest sample is
+/// evicted once `capacity` is reached.
+pub struct History {
+    capacity: usize,
+    samples: VecDeque<Sample>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, response: MetricsResponse) {
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            timestamp: now(),
+            response,
+        });
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
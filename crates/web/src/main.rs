@@ -1,26 +1,37 @@
-use std::{error::Error, sync::Arc};
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, State},
+    http::{header, StatusCode},
     response::{Html, IntoResponse},
     routing::get,
-    Router,
+    Json, Router,
 };
+use dashmap::DashMap;
 use maud::{html, Markup, Render, DOCTYPE};
 use metrics_core::{
-    metrics_service_client::MetricsServiceClient, registration_service_server::*, MetricsRequest,
-    MetricsResponse, RegistrationRequest, RegistrationResponse,
+    metrics_service_server::*, registration_service_server::*, MetricsRequest, MetricsResponse,
+    RegistrationRequest, RegistrationResponse,
 };
-use tokio::{net::TcpListener, sync::Mutex};
-use tonic::{
-    transport::{Channel, Server},
-    Request, Response, Status,
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch, Mutex},
 };
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
 use tracing::{info, level_filters::LevelFilter, warn};
 use tracing_subscriber::EnvFilter;
 
 mod config;
+mod history;
+mod prometheus;
+mod sparkline;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
@@ -34,25 +45,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     info!("Web Server Starting");
     let config = config::config().await;
 
-    let connectors = vec![];
-    let connectors = Arc::new(Mutex::new(connectors));
-    let service = MyRegistrationService::new(connectors.clone());
+    let connectors: Arc<DashMap<String, ClientEntry>> = Arc::new(DashMap::new());
+    let histories: Arc<DashMap<String, Mutex<history::History>>> = Arc::new(DashMap::new());
+    let registration_service = MyRegistrationService::default();
+    let metrics_service = MyMetricsService::new(
+        connectors.clone(),
+        histories.clone(),
+        config.history_capacity,
+    );
     let grpc_addr = format!("{}:{}", config.grpc.host, config.grpc.port)
         .parse()
         .unwrap();
 
     let handle = tokio::spawn(async move {
         Server::builder()
-            .add_service(RegistrationServiceServer::new(service))
+            .add_service(RegistrationServiceServer::new(registration_service))
+            .add_service(MetricsServiceServer::new(metrics_service))
             .serve(grpc_addr)
             .await
     });
 
-    let app_state = AppState::new(connectors);
+    let stale_after = Duration::from_secs(config.stale_after_secs);
+    let sweep_connectors = connectors.clone();
+    let sweep_histories = histories.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(stale_after / 2);
+        loop {
+            interval.tick().await;
+            sweep_connectors.retain(|host, entry| {
+                let alive = entry.last_seen.elapsed() < stale_after;
+                if !alive {
+                    warn!("Evicting stale collector {host}");
+                    sweep_histories.remove(host);
+                }
+                alive
+            });
+        }
+    });
+
+    let app_state = AppState::new(connectors, histories);
 
     let routes = Router::new()
         .route("/", get(root))
         .route("/metrics", get(metrics))
+        .route("/metrics/prometheus", get(metrics_prometheus))
+        .route("/history", get(history_handler))
         .with_state(app_state);
 
     let listener = TcpListener::bind(format!("{}:{}", config.web.host, config.web.port)).await?;
@@ -64,15 +101,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     Ok(())
 }
 
-struct MyRegistrationService {
-    connectors: Arc<Mutex<Vec<MetricsServiceClient<Channel>>>>,
+/// A collector's half of the relay: `requests` pushes a `MetricsRequest`
+/// down the stream it opened, `responses` tracks the most recent
+/// `MetricsResponse` it sent back on that same stream, and `last_seen` is
+/// refreshed on every successful round trip so the sweep task can evict
+/// collectors that have gone quiet.
+struct ClientEntry {
+    requests: mpsc::Sender<MetricsRequest>,
+    responses: watch::Receiver<MetricsResponse>,
+    last_seen: Instant,
 }
 
-impl MyRegistrationService {
-    pub fn new(connectors: Arc<Mutex<Vec<MetricsServiceClient<Channel>>>>) -> Self {
-        Self { connectors }
-    }
-}
+#[derive(Default)]
+struct MyRegistrationService;
 
 #[tonic::async_trait]
 impl RegistrationService for MyRegistrationService {
@@ -80,23 +121,7 @@ impl RegistrationService for MyRegistrationService {
         &self,
         request: Request<RegistrationRequest>,
     ) -> Result<Response<RegistrationResponse>, Status> {
-        let remote_addr = request.remote_addr().unwrap();
-        let body = request.into_inner();
-        let port = body.port;
-
-        let connection = format!("http://{}:{}", remote_addr.ip(), port);
-        info!("Trying to connect to {connection}");
-
-        let client = MetricsServiceClient::connect(connection)
-            .await
-            .map_err(|e| {
-                let source = e.source();
-                warn!("Metrics Service Connection Error: {e} {source:?}");
-                Status::internal("Could not connect to collector")
-            })?;
-
-        let mut connectors = self.connectors.lock().await;
-        connectors.push(client);
+        info!("Collector registered on port {}", request.into_inner().port);
 
         Ok(Response::new(RegistrationResponse {
             status: "OK".to_string(),
@@ -104,14 +129,94 @@ impl RegistrationService for MyRegistrationService {
     }
 }
 
+struct MyMetricsService {
+    connectors: Arc<DashMap<String, ClientEntry>>,
+    histories: Arc<DashMap<String, Mutex<history::History>>>,
+    history_capacity: usize,
+}
+
+impl MyMetricsService {
+    pub fn new(
+        connectors: Arc<DashMap<String, ClientEntry>>,
+        histories: Arc<DashMap<String, Mutex<history::History>>>,
+        history_capacity: usize,
+    ) -> Self {
+        Self {
+            connectors,
+            histories,
+            history_capacity,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl MetricsService for MyMetricsService {
+    type StreamStream = Pin<Box<dyn Stream<Item = Result<MetricsRequest, Status>> + Send>>;
+
+    async fn stream(
+        &self,
+        request: Request<Streaming<MetricsResponse>>,
+    ) -> Result<Response<Self::StreamStream>, Status> {
+        let mut inbound = request.into_inner();
+
+        let introduction = inbound.message().await?.ok_or_else(|| {
+            Status::invalid_argument("collector closed the stream before introducing itself")
+        })?;
+        let host = introduction.host.clone();
+        info!("Collector {host} connected");
+
+        let (responses_tx, responses_rx) = watch::channel(introduction);
+        let (requests_tx, requests_rx) = mpsc::channel(8);
+
+        // A re-registering collector simply overwrites its previous entry.
+        self.connectors.insert(
+            host.clone(),
+            ClientEntry {
+                requests: requests_tx,
+                responses: responses_rx,
+                last_seen: Instant::now(),
+            },
+        );
+
+        let connectors = self.connectors.clone();
+        let histories = self.histories.clone();
+        let history_capacity = self.history_capacity;
+        tokio::spawn(async move {
+            while let Ok(Some(response)) = inbound.message().await {
+                histories
+                    .entry(host.clone())
+                    .or_insert_with(|| Mutex::new(history::History::new(history_capacity)))
+                    .lock()
+                    .await
+                    .push(response.clone());
+                let _ = responses_tx.send(response);
+            }
+            info!("Collector {host} disconnected");
+            connectors.remove(&host);
+            histories.remove(&host);
+        });
+
+        Ok(Response::new(Box::pin(
+            ReceiverStream::new(requests_rx).map(Ok),
+        )))
+    }
+}
+
 #[derive(Clone)]
 struct AppState {
-    connectors: Arc<Mutex<Vec<MetricsServiceClient<Channel>>>>,
+    connectors: Arc<DashMap<String, ClientEntry>>,
+    histories: Arc<DashMap<String, Mutex<history::History>>>,
 }
 
 impl AppState {
-    pub fn new(connectors: Arc<Mutex<Vec<MetricsServiceClient<Channel>>>>) -> Self {
-        Self { connectors }
+    pub fn new(
+        connectors: Arc<DashMap<String, ClientEntry>>,
+        histories: Arc<DashMap<String, Mutex<history::History>>>,
+    ) -> Self {
+        Self {
+            connectors,
+            histories,
+        }
     }
 }
 
@@ -126,38 +231,180 @@ async fn root() -> core::result::Result<impl IntoResponse, StatusCode> {
     ))
 }
 
-async fn metrics(
-    State(app_state): State<AppState>,
-) -> core::result::Result<impl IntoResponse, StatusCode> {
-    let mut clients = app_state.connectors.lock().await;
-    let mut metrics = vec![];
-    for client in clients.iter_mut() {
-        let resp = client
-            .request_metrics(MetricsRequest {})
-            .await
-            .map_err(|e| {
-                warn!("{e}");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-        metrics.push(resp.into_inner());
+struct HostMetrics {
+    host: String,
+    result: core::result::Result<MetricsResponse, String>,
+}
+
+/// Polls every registered collector for fresh metrics. A collector that
+/// fails to respond is reported as unhealthy rather than failing the whole
+/// request, and is dropped from the registry so it doesn't keep getting
+/// polled.
+async fn fetch_metrics(app_state: &AppState) -> Vec<HostMetrics> {
+    let hosts: Vec<String> = app_state
+        .connectors
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect();
+
+    let mut metrics = Vec::with_capacity(hosts.len());
+    for host in hosts {
+        let result = request_one(&app_state.connectors, &host).await;
+        if let Err(e) = &result {
+            warn!("Dropping collector {host}: {e}");
+            app_state.connectors.remove(&host);
+            app_state.histories.remove(&host);
+        }
+        metrics.push(HostMetrics { host, result });
     }
-    Ok(Html(
+    metrics
+}
+
+async fn request_one(
+    connectors: &DashMap<String, ClientEntry>,
+    host: &str,
+) -> core::result::Result<MetricsResponse, String> {
+    let (requests, mut responses) = {
+        let entry = connectors
+            .get(host)
+            .ok_or_else(|| "collector is no longer registered".to_string())?;
+        (entry.requests.clone(), entry.responses.clone())
+    };
+
+    requests
+        .send(MetricsRequest {})
+        .await
+        .map_err(|e| e.to_string())?;
+    responses.changed().await.map_err(|e| e.to_string())?;
+    let response = responses.borrow_and_update().clone();
+
+    if let Some(mut entry) = connectors.get_mut(host) {
+        entry.last_seen = Instant::now();
+    }
+
+    Ok(response)
+}
+
+async fn memory_used_sparkline(
+    app_state: &AppState,
+    host: &str,
+) -> Markup {
+    let Some(history) = app_state.histories.get(host) else {
+        return sparkline::render(&[]);
+    };
+    let history = history.lock().await;
+    let values: Vec<f64> = history
+        .samples()
+        .map(|sample| pct_used(&sample.response) * 100.0)
+        .collect();
+    sparkline::render(&values)
+}
+
+async fn metrics(State(app_state): State<AppState>) -> impl IntoResponse {
+    let metrics = fetch_metrics(&app_state).await;
+
+    let mut sparklines = HashMap::with_capacity(metrics.len());
+    for host in &metrics {
+        if host.result.is_ok() {
+            sparklines.insert(
+                host.host.clone(),
+                memory_used_sparkline(&app_state, &host.host).await,
+            );
+        }
+    }
+
+    Html(
         html! {
             div hx-get="/metrics" hx-trigger="load delay:3s" hx-swap="outerHTML" {
-                @for metric in metrics {
-                    h2 { (metric.host) }
-                    p { "Memory Total: " (mem_total(&metric)) }
-                    p { "Memory Free: " (mem_free(&metric)) }
-                    p { "Buffers: " (buffers(&metric)) }
-                    p { "Cached: " (cached(&metric)) }
-                    p { "Memory Available: " (mem_available(&metric)) }
-                    p { "Used: " (format!("{:.2}", pct_used(&metric))) " " (used(&metric)) }
+                @for host in &metrics {
+                    @match &host.result {
+                        Ok(metric) => {
+                            h2 { (metric.host) }
+                            @if let Some(svg) = sparklines.get(&host.host) {
+                                (svg)
+                            }
+                            p { "Memory Total: " (mem_total(metric)) }
+                            p { "Memory Free: " (mem_free(metric)) }
+                            p { "Buffers: " (buffers(metric)) }
+                            p { "Cached: " (cached(metric)) }
+                            p { "Memory Available: " (mem_available(metric)) }
+                            p { "Used: " (format!("{:.2}", pct_used(metric))) " " (used(metric)) }
+                            p { "Log Lines: " (log_total_lines(metric)) " (" (log_matched_lines(metric)) " matched)" }
+                        }
+                        Err(reason) => {
+                            h2 { (host.host) " (unreachable)" }
+                            p { (reason) }
+                        }
+                    }
                 }
             }
         }
         .render()
         .into_string(),
-    ))
+    )
+}
+
+async fn metrics_prometheus(State(app_state): State<AppState>) -> impl IntoResponse {
+    let healthy: Vec<MetricsResponse> = fetch_metrics(&app_state)
+        .await
+        .into_iter()
+        .filter_map(|host| host.result.ok())
+        .collect();
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        prometheus::render(&healthy),
+    )
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    host: String,
+    metric: String,
+}
+
+#[derive(Serialize)]
+struct HistoryPoint {
+    timestamp: u64,
+    value: f64,
+}
+
+async fn history_handler(
+    State(app_state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<Vec<HistoryPoint>> {
+    let Some(history) = app_state.histories.get(&query.host) else {
+        return Json(vec![]);
+    };
+
+    let history = history.lock().await;
+    let points = history
+        .samples()
+        .filter_map(|sample| {
+            metric_value(&sample.response, &query.metric).map(|value| HistoryPoint {
+                timestamp: sample.timestamp,
+                value,
+            })
+        })
+        .collect();
+
+    Json(points)
+}
+
+fn metric_value(metric: &MetricsResponse, name: &str) -> Option<f64> {
+    match name {
+        "mem_total" => Some(mem_total(metric) as f64),
+        "mem_free" => Some(mem_free(metric) as f64),
+        "mem_available" => Some(mem_available(metric) as f64),
+        "mem_cached" => Some(cached(metric) as f64),
+        "mem_used" => Some(used(metric) as f64),
+        "mem_used_pct" => Some(pct_used(metric) * 100.0),
+        "cpu_usage" => Some(metric.cpu_usage),
+        "net_usage" => Some(metric.net_usage as f64),
+        "log_lines" => Some(log_total_lines(metric) as f64),
+        "log_matched_lines" => Some(log_matched_lines(metric) as f64),
+        _ => None,
+    }
 }
 
 fn mem_total(metric: &MetricsResponse) -> u64 {
@@ -196,6 +443,18 @@ fn cached(metric: &MetricsResponse) -> u64 {
     metric.memory.as_ref().map(|m| m.cached).unwrap_or_default()
 }
 
+fn log_total_lines(metric: &MetricsResponse) -> u64 {
+    metric.log.as_ref().map(|l| l.total_lines).unwrap_or_default()
+}
+
+fn log_matched_lines(metric: &MetricsResponse) -> u64 {
+    metric
+        .log
+        .as_ref()
+        .map(|l| l.matched_lines)
+        .unwrap_or_default()
+}
+
 fn used(metric: &MetricsResponse) -> u64 {
     let total = mem_total(metric);
     let free = mem_free(metric);
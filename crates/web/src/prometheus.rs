@@ -0,0 +1,100 @@
+use metrics_core::MetricsResponse;
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct HostLabels {
+    host: String,
+}
+
+/// Renders the given collector responses as Prometheus text-format exposition,
+/// one family per metric, labeled by host: gauges for memory, CPU and network
+/// values, counters for the log line counts.
+pub fn render(metrics: &[MetricsResponse]) -> String {
+    let mut registry = Registry::default();
+
+    let mem_total = Family::<HostLabels, Gauge>::default();
+    let mem_available = Family::<HostLabels, Gauge>::default();
+    let mem_used = Family::<HostLabels, Gauge>::default();
+    let mem_cached = Family::<HostLabels, Gauge>::default();
+    let cpu_usage = Family::<HostLabels, Gauge<f64, std::sync::atomic::AtomicU64>>::default();
+    let net_usage = Family::<HostLabels, Gauge>::default();
+    let log_lines = Family::<HostLabels, Counter>::default();
+    let log_matched_lines = Family::<HostLabels, Counter>::default();
+
+    registry.register(
+        "server_stats_mem_total_bytes",
+        "Total system memory in bytes",
+        mem_total.clone(),
+    );
+    registry.register(
+        "server_stats_mem_available_bytes",
+        "Memory available for new allocations, in bytes",
+        mem_available.clone(),
+    );
+    registry.register(
+        "server_stats_mem_used_bytes",
+        "Memory in use, in bytes",
+        mem_used.clone(),
+    );
+    registry.register(
+        "server_stats_mem_cached_bytes",
+        "Memory used for the page cache, in bytes",
+        mem_cached.clone(),
+    );
+    registry.register(
+        "server_stats_cpu_usage_ratio",
+        "CPU usage as a ratio between 0 and 1",
+        cpu_usage.clone(),
+    );
+    registry.register(
+        "server_stats_net_bytes",
+        "Network throughput, in bytes per second",
+        net_usage.clone(),
+    );
+    registry.register(
+        "server_stats_log_lines",
+        "Total log lines tailed since the collector started",
+        log_lines.clone(),
+    );
+    registry.register(
+        "server_stats_log_matched_lines",
+        "Log lines matching the collector's configured pattern since it started",
+        log_matched_lines.clone(),
+    );
+
+    for metric in metrics {
+        let labels = HostLabels {
+            host: metric.host.clone(),
+        };
+        let memory = metric.memory.clone().unwrap_or_default();
+        let used = memory
+            .mem_total
+            .saturating_sub(memory.mem_free)
+            .saturating_sub(memory.buffers)
+            .saturating_sub(memory.cached);
+
+        mem_total.get_or_create(&labels).set(memory.mem_total as i64);
+        mem_available
+            .get_or_create(&labels)
+            .set(memory.mem_available as i64);
+        mem_used.get_or_create(&labels).set(used as i64);
+        mem_cached.get_or_create(&labels).set(memory.cached as i64);
+        cpu_usage.get_or_create(&labels).set(metric.cpu_usage);
+        net_usage.get_or_create(&labels).set(metric.net_usage as i64);
+
+        let log = metric.log.clone().unwrap_or_default();
+        log_lines.get_or_create(&labels).inc_by(log.total_lines);
+        log_matched_lines
+            .get_or_create(&labels)
+            .inc_by(log.matched_lines);
+    }
+
+    let mut buffer = String::new();
+    encode(&mut buffer, &registry).expect("registry encoding is infallible");
+    buffer
+}
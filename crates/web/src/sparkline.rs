@@ -0,0 +1,29 @@
+use maud::{html, Markup};
+
+const WIDTH: f64 = 120.0;
+const HEIGHT: f64 = 24.0;
+
+/// Renders a small inline SVG sparkline for a series of percentage values
+/// (0-100), oldest first.
+pub fn render(values: &[f64]) -> Markup {
+    if values.len() < 2 {
+        return html! { svg width=(WIDTH) height=(HEIGHT) {} };
+    }
+
+    let step = WIDTH / (values.len() - 1) as f64;
+    let points: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let x = i as f64 * step;
+            let y = HEIGHT - (value.clamp(0.0, 100.0) / 100.0 * HEIGHT);
+            format!("{x:.1},{y:.1}")
+        })
+        .collect();
+
+    html! {
+        svg width=(WIDTH) height=(HEIGHT) viewBox=(format!("0 0 {WIDTH} {HEIGHT}")) {
+            polyline points=(points.join(" ")) fill="none" stroke="currentColor" stroke-width="1.5" {}
+        }
+    }
+}